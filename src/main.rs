@@ -1,5 +1,13 @@
-use clap::{Arg, Command};
-use rachel_project::{scanner::build_scanner, tmpl_ops};
+use clap::{Arg, ArgAction, Command};
+use futures::StreamExt;
+use rachel_project::{
+    findings::Severity,
+    rules,
+    scanner::{Checkpoint, ScanReport, ScanResults, build_scanner},
+    tmpl_ops::{self, Keywords},
+};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -23,6 +31,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .help("File to parse")
                         .required(true)
                         .index(1),
+                )
+                .arg(
+                    Arg::new("checkpoint")
+                        .long("checkpoint")
+                        .value_name("PATH")
+                        .help("Checkpoint file (defaults to <file>.ckpt)"),
+                )
+                .arg(
+                    Arg::new("resume")
+                        .long("resume")
+                        .action(ArgAction::SetTrue)
+                        .help("Continue an interrupted scan from its checkpoint"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "json"])
+                        .default_value("text")
+                        .help("Output format for scan results"),
+                )
+                .arg(
+                    Arg::new("graph")
+                        .long("graph")
+                        .value_name("PATH")
+                        .help("Write the crawl link graph to PATH"),
+                )
+                .arg(
+                    Arg::new("graph-format")
+                        .long("graph-format")
+                        .value_parser(["dot", "json", "tree"])
+                        .help("Link graph format (default: from the extension, else tree)"),
+                ),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check the syntax of a rule file, or of the rule files a .rchl references")
+                .arg(
+                    Arg::new("file")
+                        .help("Rule file or .rchl template")
+                        .required(true)
+                        .index(1),
                 ),
         )
         .get_matches();
@@ -51,7 +100,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         // ------------ handle parsing + scanning
         Some(("parse", sub_m)) => {
             let filename = sub_m.get_one::<String>("file").unwrap();
-            println!("Parsing file: {}", filename);
+            let json = sub_m.get_one::<String>("format").map(String::as_str) == Some("json");
+            // keep stdout clean for the json document
+            let info = |msg: String| {
+                if json {
+                    eprintln!("{}", msg);
+                } else {
+                    println!("{}", msg);
+                }
+            };
+            info(format!("Parsing file: {}", filename));
 
             let contents = match tmpl_ops::read_file(filename) {
                 Ok(c) => c,
@@ -62,61 +120,391 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             };
 
             // build scanner from parsed contents
-            let scanner = build_scanner(contents);
-
-            println!("Starting scan for target: {}", scanner.target);
-            let results = scanner.run().await;
-
-            for r in results.iter() {
-                println!("=== URL: {} ===", r.url);
-                println!("Status: {}", r.status_code);
-                if let Some(snippet) = &r.body_snippet {
-                    println!("Snippet ({} chars):", snippet.chars().count());
-                    let s: String = snippet.chars().take(400).collect();
-                    println!("{}", s);
-                }
-                if !r.headers.is_empty() {
-                    println!("Headers:");
-                    for (k, v) in &r.headers {
-                        println!("  {}: {}", k, v);
+            let mut scanner = build_scanner(contents);
+
+            // checkpoints are always written so an interrupted scan can be resumed
+            let checkpoint = sub_m
+                .get_one::<String>("checkpoint")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!("{}.ckpt", filename)));
+            if sub_m.get_flag("resume") {
+                let resumed = Checkpoint::load(&checkpoint).and_then(|cp| scanner.resume(cp));
+                match resumed {
+                    Ok(_) => info(format!(
+                        "Resuming from checkpoint: {}",
+                        checkpoint.display()
+                    )),
+                    Err(e) => {
+                        eprintln!("Failed to resume from '{}': {}", checkpoint.display(), e);
+                        std::process::exit(1);
                     }
                 }
-                if !r.input_fields.is_empty() {
-                    println!("Discovered input fields:");
-                    for f in &r.input_fields {
-                        // uses Display impl for InputField
-                        println!("  {}", f);
-                        // optionally show some more details:
-                        if let Some(name) = &f.name {
-                            println!("    name: {}", name);
-                        }
-                        if let Some(id) = &f.id {
-                            println!("    id: {}", id);
-                        }
-                        if let Some(val) = &f.value {
-                            let display_len = std::cmp::min(val.len(), 80);
-                            println!("    value (len={}): {}", val.len(), &val[..display_len]);
-                        }
-                        if let Some(prob) = f.probable_secret {
-                            println!("    probable_secret: {}", prob);
-                        }
-                        if let Some(entropy) = f.secret_entropy {
-                            println!("    entropy: {:.2}", entropy);
-                        }
-                    }
+            }
+            scanner.checkpoint = Some(checkpoint);
+
+            // first Ctrl-C winds the scan down gracefully, a second one bails out
+            let stop = scanner.stop_handle();
+            let grace = scanner.grace_period;
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    eprintln!(
+                        "Interrupted: giving in-flight requests {}s, Ctrl-C again to abort",
+                        grace.as_secs()
+                    );
+                    stop.stop();
+                }
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    std::process::exit(130);
+                }
+            });
+
+            info(format!("Starting scan for target: {}", scanner.target));
+            // text output prints every page as soon as it's scanned
+            let mut stream = scanner.run_stream();
+            let mut results = Vec::new();
+            while let Some(r) = stream.next().await {
+                if !json {
+                    print_result(&r);
                 }
-                if let Some(err) = &r.errors {
-                    println!("Errors: {}", err);
+                results.push(r);
+            }
+
+            let report = ScanReport {
+                target: scanner.target.clone(),
+                complete: !scanner.stop_handle().is_stopped(),
+                results,
+                summary: scanner.summary(),
+            };
+            if let Some(path) = sub_m.get_one::<String>("graph") {
+                let format = sub_m
+                    .get_one::<String>("graph-format")
+                    .map(String::as_str)
+                    .unwrap_or_else(|| graph_format_for(path));
+                let graph = &report.summary.graph;
+                let rendered = match format {
+                    "dot" => graph.to_dot(),
+                    "json" => graph.to_json()?,
+                    _ => graph.to_tree(),
+                };
+                match std::fs::write(path, rendered) {
+                    Ok(_) => info(format!("Link graph written: {} ({})", path, format)),
+                    Err(e) => eprintln!("Failed to write link graph '{}': {}", path, e),
                 }
-                println!("---------------------------");
             }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_summary(&report);
+            }
+            if !report.complete {
+                std::process::exit(130);
+            }
+        }
+
+        // ------------ handle rule file validation
+        Some(("validate", sub_m)) => {
+            let filename = sub_m.get_one::<String>("file").unwrap();
+            let files = if filename.ends_with(".rchl") {
+                match tmpl_ops::read_file(filename) {
+                    Ok(contents) => contents
+                        .into_iter()
+                        .filter_map(|k| match k {
+                            Keywords::Rules(path) => Some(path),
+                            _ => None,
+                        })
+                        .collect(),
+                    Err(e) => {
+                        eprintln!("Failed to read file '{}': {}", filename, e);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                vec![filename.clone()]
+            };
 
-            Ok(())
+            let mut failed = false;
+            let mut rule_ids = HashSet::new();
+            for path in &files {
+                match rules::load(Path::new(path)).and_then(|loaded| {
+                    rules::claim_ids(&loaded, &mut rule_ids).map_err(std::io::Error::other)?;
+                    Ok(loaded)
+                }) {
+                    Ok(loaded) => println!("{}: ok ({} rules)", path, loaded.len()),
+                    Err(e) => {
+                        eprintln!("{}: {}", path, e);
+                        failed = true;
+                    }
+                }
+            }
+            if files.is_empty() {
+                println!("{}: no rule files referenced", filename);
+            }
+            if failed {
+                std::process::exit(1);
+            }
         }
 
         _ => {
-            println!("No valid subcommand provided. Use 'gen' or 'parse'.");
-            Ok(())
+            println!("No valid subcommand provided. Use 'gen', 'parse' or 'validate'.");
         }
     }
+
+    Ok(())
+}
+
+/// Pick the graph format from the output file extension.
+fn graph_format_for(path: &str) -> &'static str {
+    match std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .as_deref()
+    {
+        Some("dot" | "gv") => "dot",
+        Some("json") => "json",
+        _ => "tree",
+    }
+}
+
+fn print_result(r: &ScanResults) {
+    println!("=== URL: {} ===", r.url);
+    println!("Status: {}", r.status_code);
+    if let Some(ct) = &r.content_type {
+        println!("Content-Type: {}", ct);
+    }
+    println!(
+        "Body: {} bytes read{}{}",
+        r.body_bytes,
+        r.content_length
+            .map(|l| format!(" (announced {})", l))
+            .unwrap_or_default(),
+        if r.body_truncated { ", TRUNCATED" } else { "" }
+    );
+    if let Some(charset) = &r.charset {
+        println!("Charset: {}", charset);
+    }
+    if let Some(hash) = &r.body_sha256 {
+        println!("SHA-256: {}", hash);
+    }
+    if let Some(snippet) = &r.body_snippet {
+        println!("Snippet ({} chars):", snippet.chars().count());
+        let s: String = snippet.chars().take(400).collect();
+        println!("{}", s);
+    }
+    if !r.headers.is_empty() {
+        println!("Headers:");
+        for (k, v) in &r.headers {
+            println!("  {}: {}", k, v);
+        }
+    }
+    if !r.input_fields.is_empty() {
+        println!("Discovered input fields:");
+        for f in &r.input_fields {
+            // uses Display impl for InputField
+            println!("  {}", f);
+            // optionally show some more details:
+            if let Some(name) = &f.name {
+                println!("    name: {}", name);
+            }
+            if let Some(id) = &f.id {
+                println!("    id: {}", id);
+            }
+            if let Some(val) = &f.value {
+                // slice on chars, not bytes: values may be multi-byte after decoding
+                let shown: String = val.chars().take(80).collect();
+                println!("    value (len={}): {}", val.chars().count(), shown);
+            }
+            if let Some(action) = &f.form_action {
+                println!(
+                    "    form: {} {}{}",
+                    f.form_method.as_deref().unwrap_or("get").to_uppercase(),
+                    action,
+                    f.form_id
+                        .as_ref()
+                        .map(|id| format!(" (#{})", id))
+                        .unwrap_or_default()
+                );
+            }
+            if f.is_hidden == Some(true) {
+                println!("    hidden: true");
+            }
+            if let Some(prob) = f.probable_secret {
+                println!("    probable_secret: {}", prob);
+            }
+            if let Some(entropy) = f.secret_entropy {
+                println!("    entropy: {:.2}", entropy);
+            }
+        }
+    }
+    if !r.forms.is_empty() {
+        println!("Forms ({}):", r.forms.len());
+        for form in &r.forms {
+            println!(
+                "  {} {} ({}){}",
+                form.method.to_uppercase(),
+                form.action
+                    .as_ref()
+                    .map(|u| u.to_string())
+                    .unwrap_or_else(|| "<unresolved>".to_string()),
+                form.enctype,
+                form.id
+                    .as_ref()
+                    .map(|id| format!(" #{}", id))
+                    .unwrap_or_default()
+            );
+            let names: Vec<&str> = form
+                .fields
+                .iter()
+                .map(|f| f.name.as_deref().unwrap_or("?"))
+                .collect();
+            println!("    fields: {}", names.join(", "));
+            for control in &form.submits {
+                let mut overrides = Vec::new();
+                if let Some(action) = &control.formaction {
+                    overrides.push(format!("formaction={}", action));
+                }
+                if let Some(method) = &control.formmethod {
+                    overrides.push(format!("formmethod={}", method));
+                }
+                if let Some(enctype) = &control.formenctype {
+                    overrides.push(format!("formenctype={}", enctype));
+                }
+                println!(
+                    "    submit: <{} {}> {:?}{}",
+                    control.tag_name,
+                    control.control_type,
+                    control.label,
+                    if overrides.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [{}]", overrides.join(", "))
+                    }
+                );
+            }
+        }
+    }
+    if !r.orphan_fields.is_empty() {
+        let names: Vec<&str> = r
+            .orphan_fields
+            .iter()
+            .map(|f| f.name.as_deref().unwrap_or("?"))
+            .collect();
+        println!("Fields outside any form: {}", names.join(", "));
+    }
+    if !r.links.is_empty() {
+        println!("Links ({}):", r.links.len());
+        for l in &r.links {
+            println!("  {} <- {}", l.url, l.source);
+        }
+    }
+    if let Some(map) = &r.source_map {
+        println!(
+            "Source map exposed: {} (via {}, {} sources, {} with full content)",
+            map.map_url,
+            map.discovered_via,
+            map.sources.len(),
+            map.sources_with_content
+        );
+        for src in &map.sources {
+            println!("  {}", src);
+        }
+    }
+    if let Some(sub) = &r.submitted {
+        println!(
+            "Response to form POST: {}",
+            sub.body.as_deref().unwrap_or("")
+        );
+    }
+    if !r.submissions.is_empty() {
+        println!("Form submissions:");
+        for sub in &r.submissions {
+            match &sub.body {
+                Some(body) => println!("  {} {} [{}]", sub.method.to_uppercase(), sub.url, body),
+                None => println!("  {} {}", sub.method.to_uppercase(), sub.url),
+            }
+        }
+    }
+    if !r.secrets.is_empty() {
+        println!("Possible secrets:");
+        for sec in &r.secrets {
+            let shown: String = sec.value.chars().take(80).collect();
+            println!("  [{}] {} (in {})", sec.kind, shown, sec.found_in);
+        }
+    }
+    if !r.findings.is_empty() {
+        println!("Findings:");
+        for finding in &r.findings {
+            println!(
+                "  [{}/{}] {}: {} @ {}",
+                finding.severity,
+                finding.confidence,
+                finding.rule_id,
+                finding.title,
+                finding.location
+            );
+            let evidence: String = finding.evidence.chars().take(160).collect();
+            if !evidence.is_empty() {
+                println!("    evidence: {}", evidence.replace('\n', " "));
+            }
+            println!("    fix: {}", finding.remediation);
+        }
+    }
+    if let Some(err) = &r.errors {
+        println!("Errors: {}", err);
+    }
+    println!("---------------------------");
+}
+
+fn print_summary(report: &ScanReport) {
+    if !report.summary.traps.is_empty() {
+        println!("Crawler traps (skipped after the variant cap):");
+        for trap in &report.summary.traps {
+            println!(
+                "  {} ({} variants, e.g. {})",
+                trap.pattern, trap.variants, trap.example
+            );
+        }
+    }
+    if !report.summary.js_endpoints.is_empty() {
+        println!("JS-discovered endpoints:");
+        for ep in &report.summary.js_endpoints {
+            println!("  [{}] {} (in {})", ep.kind, ep.endpoint, ep.found_in);
+        }
+    }
+    if !report.summary.skipped.is_empty() {
+        println!("Skipped by the denylist (test these by hand):");
+        for skip in &report.summary.skipped {
+            println!(
+                "  {} (matched '{}', {} on {})",
+                skip.url, skip.reason, skip.source, skip.found_on
+            );
+        }
+    }
+    let orphans = report.summary.graph.orphans();
+    if !orphans.is_empty() {
+        println!("Orphan pages (in the scope list, not linked from any crawled page):");
+        for node in orphans {
+            println!("  {}", node.url);
+        }
+    }
+    let mut by_severity: BTreeMap<Severity, usize> = BTreeMap::new();
+    for finding in report.results.iter().flat_map(|r| &r.findings) {
+        *by_severity.entry(finding.severity).or_default() += 1;
+    }
+    if !by_severity.is_empty() {
+        let counts: Vec<String> = by_severity
+            .iter()
+            .rev()
+            .map(|(s, n)| format!("{} {}", n, s))
+            .collect();
+        println!("Findings: {}", counts.join(", "));
+    }
+    if report.complete {
+        println!("Scan finished: {} pages", report.results.len());
+    } else {
+        println!(
+            "Scan interrupted: {} pages (INCOMPLETE, resume with --resume)",
+            report.results.len()
+        );
+    }
 }